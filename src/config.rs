@@ -0,0 +1,59 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use serde::Deserialize;
+
+/// User-supplied overrides for how OBIS fields map onto metrics, loaded from
+/// the file passed via `--config`. Absent entries fall back to the exporter's
+/// built-in defaults.
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    #[serde(default, rename = "metric")]
+    metrics: HashMap<String, MetricConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MetricConfig {
+    name: Option<String>,
+    #[serde(default = "default_enabled")]
+    enabled: bool,
+    scale: Option<f64>,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl Config {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        toml::from_str(&contents)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()))
+    }
+
+    pub fn is_enabled(&self, id: &str) -> bool {
+        self.metrics.get(id).map_or(true, |metric| metric.enabled)
+    }
+
+    pub fn name(&self, id: &str, default: &str) -> String {
+        self.metrics
+            .get(id)
+            .and_then(|metric| metric.name.clone())
+            .unwrap_or_else(|| default.to_string())
+    }
+
+    pub fn scale(&self, id: &str, default: f64) -> f64 {
+        self.metrics
+            .get(id)
+            .and_then(|metric| metric.scale)
+            .unwrap_or(default)
+    }
+
+    pub fn labels(&self, id: &str) -> Vec<(String, String)> {
+        self.metrics
+            .get(id)
+            .map(|metric| metric.labels.clone().into_iter().collect())
+            .unwrap_or_default()
+    }
+}
@@ -1,7 +1,50 @@
-use std::io;
+use std::{fmt, io};
 use bytes::{Buf, BytesMut};
 use tokio_util::codec::Decoder;
 
+/// Why a telegram failed to decode, used to label the
+/// `dsmr_decode_errors_total` metric.
+#[derive(Debug)]
+pub enum DecodeError {
+    Framing(String),
+    Crc { expected: Option<u16>, calculated: u16 },
+    Telegram(String),
+}
+
+impl DecodeError {
+    pub fn cause(&self) -> &'static str {
+        match self {
+            DecodeError::Framing(_) => "framing",
+            DecodeError::Crc { .. } => "crc",
+            DecodeError::Telegram(_) => "telegram",
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Framing(message) => write!(f, "{}", message),
+            DecodeError::Crc { expected, calculated } => write!(
+                f,
+                "CRC mismatch: expected {:?}, calculated {:04X}",
+                expected, calculated
+            ),
+            DecodeError::Telegram(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// Required by `tokio_util::codec::Decoder`, which needs to convert I/O
+// errors from the underlying transport into the codec's own error type.
+impl From<io::Error> for DecodeError {
+    fn from(error: io::Error) -> Self {
+        DecodeError::Framing(error.to_string())
+    }
+}
+
 pub struct Dsmr5Codec {}
 
 impl Dsmr5Codec {
@@ -12,11 +55,11 @@ impl Dsmr5Codec {
 
 impl Decoder for Dsmr5Codec {
     type Item = dsmr5::state::State;
-    type Error = io::Error;
+    type Error = DecodeError;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         if src.len() > 2048 {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Received frame longer than max"));
+            return Err(DecodeError::Framing("Received frame longer than max".into()));
         }
 
         if src.capacity() < 2048 {
@@ -32,9 +75,17 @@ impl Decoder for Dsmr5Codec {
             },
         };
 
-        let end_index = match src.as_ref().iter().position(|b| *b == b'!') {
+        let exclamation_index = match src.as_ref().iter().position(|b| *b == b'!') {
             None => return Ok(None),
-            Some(index) => index + 7,
+            Some(index) => index,
+        };
+
+        // DSMR4 telegrams terminate with a bare `!\r\n` and carry no CRC.
+        let has_crc = src.get(exclamation_index + 1) != Some(&b'\r');
+        let end_index = if has_crc {
+            exclamation_index + 7
+        } else {
+            exclamation_index + 3
         };
 
         if src.len() < end_index {
@@ -42,16 +93,52 @@ impl Decoder for Dsmr5Codec {
         }
 
         let mut frame = src.split_to(end_index);
+
+        if has_crc {
+            let expected_crc = std::str::from_utf8(&frame[exclamation_index + 1..exclamation_index + 5])
+                .ok()
+                .and_then(|hex| u16::from_str_radix(hex, 16).ok());
+
+            let calculated_crc = crc16_arc(&frame[..=exclamation_index]);
+
+            if expected_crc != Some(calculated_crc) {
+                return Err(DecodeError::Crc {
+                    expected: expected_crc,
+                    calculated: calculated_crc,
+                });
+            }
+        }
+
         frame.resize(2048, 0);
 
         let readout = dsmr5::Readout { buffer: frame.as_ref().try_into().unwrap() };
         let telegram = readout.to_telegram().map_err(|err|
-            io::Error::new(io::ErrorKind::InvalidData, format!("Failed to decode telegram: {:?}", err))
+            DecodeError::Telegram(format!("Failed to decode telegram: {:?}", err))
         )?;
         let state = dsmr5::Result::from(&telegram).map_err(|err|
-            io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", err))
+            DecodeError::Telegram(format!("{:?}", err))
         )?;
 
         Ok(Some(state))
     }
 }
+
+/// CRC16/ARC over the telegram bytes from the leading `/` up to and
+/// including the final `!`.
+fn crc16_arc(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0x0000;
+
+    for &byte in data {
+        crc ^= byte as u16;
+
+        for _ in 0..8 {
+            if crc & 0x0001 != 0 {
+                crc = (crc >> 1) ^ 0xA001;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+
+    crc
+}
@@ -0,0 +1,8 @@
+pub mod config;
+pub mod decoder;
+pub mod metrics;
+pub mod mqtt;
+pub mod transport;
+
+pub use config::Config;
+pub use metrics::Metrics;
@@ -1,7 +1,12 @@
 use axum::{http::StatusCode, response::IntoResponse, routing::get, Router};
 use backoff::{future::retry, ExponentialBackoffBuilder};
 use clap::Parser;
-use dsmr5_exporter::{decoder, metrics::METRICS_TTL, Metrics};
+use dsmr5_exporter::{
+    decoder,
+    mqtt::{self, MqttConfig},
+    transport::Port,
+    Config, Metrics,
+};
 use futures::StreamExt;
 use std::{
     net::{IpAddr, Ipv4Addr, SocketAddr},
@@ -14,28 +19,59 @@ use tokio::{
     signal::unix::{signal, SignalKind},
     sync::{broadcast, RwLock},
 };
-use tokio_serial::SerialPortBuilderExt;
 use tokio_util::codec::Decoder;
 
 #[derive(Parser, Debug)]
 #[clap(name = "dsmr5-exporter", version, author)]
 struct Cli {
-    serial_device_path: PathBuf,
+    /// Path to the local serial device, or a `tcp://host:port` address of a
+    /// ser2net/ESP bridge exposing the meter's P1 port over the network.
+    device: String,
 
     #[arg(long, default_value_t = Ipv4Addr::new(127, 0, 0, 1))]
     host: Ipv4Addr,
 
     #[arg(long, default_value_t = 3000)]
     port: u16,
+
+    /// Broker to publish decoded state to over MQTT. When unset, MQTT
+    /// publishing is disabled entirely.
+    #[arg(long)]
+    mqtt_host: Option<String>,
+
+    #[arg(long, default_value_t = 1883)]
+    mqtt_port: u16,
+
+    #[arg(long, default_value = "dsmr5")]
+    mqtt_topic_prefix: String,
+
+    /// Path to a TOML file describing how OBIS fields map onto metrics. See
+    /// `Config` for the supported `[metric.<id>]` keys.
+    #[arg(long)]
+    config: Option<PathBuf>,
 }
 
 #[tokio::main]
 pub async fn main() {
     let cli = Cli::parse();
 
+    let config = match &cli.config {
+        Some(path) => Config::load(path).unwrap_or_else(|error| {
+            eprintln!("failed to load config {}: {:?}", path.display(), error);
+            std::process::exit(1);
+        }),
+        None => Config::default(),
+    };
+
+    let metrics = Metrics::new(&config).unwrap_or_else(|error| {
+        eprintln!("failed to initialize metrics: {:?}", error);
+        std::process::exit(1);
+    });
+
     let mut sigterm = signal(SignalKind::terminate()).unwrap();
     let (notify_shutdown, _) = broadcast::channel::<()>(1);
-    let metrics = Arc::new(RwLock::new(Metrics::new()));
+    let (state_tx, _) = broadcast::channel::<Arc<dsmr5::state::State>>(16);
+    let metrics = Arc::new(RwLock::new(metrics));
 
     let server_task = tokio::spawn(serve(
         SocketAddr::new(IpAddr::V4(cli.host), cli.port),
@@ -43,8 +79,21 @@ pub async fn main() {
         notify_shutdown.subscribe(),
     ));
 
+    let mqtt_task = cli.mqtt_host.clone().map(|host| {
+        let config = MqttConfig {
+            host,
+            port: cli.mqtt_port,
+            topic_prefix: cli.mqtt_topic_prefix.clone(),
+        };
+        tokio::spawn(mqtt::run(
+            config,
+            state_tx.subscribe(),
+            notify_shutdown.subscribe(),
+        ))
+    });
+
     tokio::select! {
-        _ = read(cli.serial_device_path.to_str().unwrap(), Arc::clone(&metrics)) => {},
+        _ = read(&cli.device, Arc::clone(&metrics), state_tx) => {},
         _ = sigterm.recv() => {
             println!("received sigterm, stopping");
         },
@@ -55,26 +104,35 @@ pub async fn main() {
 
     drop(notify_shutdown);
     let _ = server_task.await;
+    if let Some(mqtt_task) = mqtt_task {
+        let _ = mqtt_task.await;
+    }
 }
 
 async fn read(
-    serial_device: &str,
+    device: &str,
     metrics: Arc<RwLock<Metrics>>,
-) -> Result<(), tokio_serial::Error> {
+    states: broadcast::Sender<Arc<dsmr5::state::State>>,
+) -> Result<(), std::io::Error> {
     let backoff = ExponentialBackoffBuilder::default()
         .with_max_interval(Duration::from_millis(5000))
         .with_max_elapsed_time(None)
         .build();
 
+    let mut is_first_attempt = true;
+
     retry::<(), _, _, _, _>(backoff, || async {
-        println!("opening serial port");
-        let port = tokio_serial::new(serial_device, 115200)
-            .open_native_async()
-            .tap(|result| {
-                if let Err(error) = result {
-                    println!("failed to open serial port: {:?}", error);
-                }
-            })?;
+        if is_first_attempt {
+            is_first_attempt = false;
+        } else {
+            metrics.write().await.record_reconnect();
+        }
+
+        let port = Port::open(device).await.tap(|result| {
+            if let Err(error) = result {
+                println!("failed to open {}: {:?}", device, error);
+            }
+        })?;
 
         let mut reader = decoder::Dsmr5Codec::new().framed(port);
         println!("port open");
@@ -85,14 +143,19 @@ async fn read(
                     println!("frame received: {:?}", frame);
                     let mut metrics = metrics.write().await;
                     metrics.update(&frame);
+                    metrics.record_frame_decoded();
+                    let _ = states.send(Arc::new(frame));
+                }
+                Err(error) => {
+                    println!("error reading frame: {:?}", error);
+                    metrics.write().await.record_decode_error(error.cause());
                 }
-                Err(error) => println!("error reading frame: {:?}", error),
             }
         }
 
-        Err(backoff::Error::transient(tokio_serial::Error::new(
-            tokio_serial::ErrorKind::Io(std::io::ErrorKind::ConnectionReset),
-            "serial read stream ended",
+        Err(backoff::Error::transient(std::io::Error::new(
+            std::io::ErrorKind::ConnectionReset,
+            "read stream ended",
         )))
     })
     .await
@@ -127,10 +190,6 @@ async fn serve(
 async fn handler(metrics: Arc<RwLock<Metrics>>) -> Result<String, StatusCode> {
     let metrics = metrics.read().await;
 
-    if metrics.last_update.elapsed() > METRICS_TTL {
-        return Ok(String::new());
-    }
-
     metrics.encode().map_err(|error| {
         println!("Error while encoding metrics: {:?}", error);
         StatusCode::INTERNAL_SERVER_ERROR
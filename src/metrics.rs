@@ -4,230 +4,396 @@ use prometheus::{
 };
 use std::{
     error,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+use crate::config::Config;
+
 pub const METRICS_TTL: Duration = Duration::from_millis(10_000);
 
 pub struct Metrics {
     encoder: TextEncoder,
     registry: Registry,
+    internal_registry: Registry,
     pub last_update: Instant,
 
-    energy_delivered_joules_total: CounterVec,
-    energy_received_joules_total: CounterVec,
-    energy_tariff: IntGauge,
-    power_delivered_watts: Gauge,
-    power_received_watts: Gauge,
-    power_failures_total: IntCounter,
-    power_long_failures_total: IntCounter,
-    phase_voltage_sags_total: IntCounterVec,
-    phase_voltage_swells_total: IntCounterVec,
-    phase_voltage_volts: GaugeVec,
-    phase_current_amperes: GaugeVec,
-    phase_active_power_positive_watts: GaugeVec,
-    phase_active_power_negative_watts: GaugeVec,
-    gas_delivered_cubic_meters_total: Counter,
+    energy_delivered_joules_total: Option<CounterVec>,
+    energy_delivered_scale: f64,
+    energy_received_joules_total: Option<CounterVec>,
+    energy_received_scale: f64,
+    energy_tariff: Option<IntGauge>,
+    power_delivered_watts: Option<Gauge>,
+    power_delivered_scale: f64,
+    power_received_watts: Option<Gauge>,
+    power_received_scale: f64,
+    power_failures_total: Option<IntCounter>,
+    power_long_failures_total: Option<IntCounter>,
+    phase_voltage_sags_total: Option<IntCounterVec>,
+    phase_voltage_swells_total: Option<IntCounterVec>,
+    phase_voltage_volts: Option<GaugeVec>,
+    phase_voltage_scale: f64,
+    phase_current_amperes: Option<GaugeVec>,
+    phase_current_scale: f64,
+    phase_active_power_positive_watts: Option<GaugeVec>,
+    phase_active_power_positive_scale: f64,
+    phase_active_power_negative_watts: Option<GaugeVec>,
+    phase_active_power_negative_scale: f64,
+    gas_delivered_cubic_meters_total: Option<Counter>,
+    gas_delivered_scale: f64,
+
+    frames_decoded_total: IntCounter,
+    decode_errors_total: IntCounterVec,
+    serial_reconnects_total: IntCounter,
+    last_frame_timestamp_seconds: Gauge,
+}
+
+/// Builds `Opts` for metric `id`, applying the name override and static
+/// labels from `config`, if any.
+fn opts(config: &Config, id: &str, default_name: &str, help: &str) -> Opts {
+    let mut opts = Opts::new(config.name(id, default_name), help);
+
+    for (name, value) in config.labels(id) {
+        opts = opts.const_label(name, value);
+    }
+
+    opts
+}
+
+/// Registers `metric` in `registry` unless `id` is disabled in `config`.
+/// Bubbles up naming/label collisions from the config instead of panicking.
+fn register<T: prometheus::core::Collector + Clone + 'static>(
+    registry: &Registry,
+    config: &Config,
+    id: &str,
+    metric: Result<T, prometheus::Error>,
+) -> Result<Option<T>, prometheus::Error> {
+    if !config.is_enabled(id) {
+        return Ok(None);
+    }
+
+    let metric = metric?;
+    registry.register(Box::new(metric.clone()))?;
+
+    Ok(Some(metric))
 }
 
 impl Metrics {
-    pub fn new() -> Self {
+    pub fn new(config: &Config) -> Result<Self, prometheus::Error> {
         let registry = Registry::new();
-
-        let energy_delivered_joules_total = CounterVec::new(
-            Opts::new(
-                "energy_delivered_joules_total",
-                "The amount of energy delivered to client in joules",
+        let internal_registry = Registry::new();
+
+        let energy_delivered_joules_total = register(
+            &registry,
+            config,
+            "energy_delivered",
+            CounterVec::new(
+                opts(
+                    config,
+                    "energy_delivered",
+                    "energy_delivered_joules_total",
+                    "The amount of energy delivered to client in joules",
+                ),
+                &["tariff"],
             ),
-            &["tariff"],
-        )
-        .unwrap();
-        registry
-            .register(Box::new(energy_delivered_joules_total.clone()))
-            .unwrap();
-
-        let energy_received_joules_total = CounterVec::new(
-            Opts::new(
-                "energy_received_joules_total",
-                "The amount of energy delivered by client in joules",
+        )?;
+        let energy_delivered_scale = config.scale("energy_delivered", 3600000.0);
+
+        let energy_received_joules_total = register(
+            &registry,
+            config,
+            "energy_received",
+            CounterVec::new(
+                opts(
+                    config,
+                    "energy_received",
+                    "energy_received_joules_total",
+                    "The amount of energy delivered by client in joules",
+                ),
+                &["tariff"],
             ),
-            &["tariff"],
-        )
-        .unwrap();
-        registry
-            .register(Box::new(energy_received_joules_total.clone()))
-            .unwrap();
-
-        let energy_tariff =
-            IntGauge::with_opts(Opts::new("energy_tariff", "The currently active tariff")).unwrap();
-        registry.register(Box::new(energy_tariff.clone())).unwrap();
-
-        let power_delivered_watts = Gauge::with_opts(Opts::new(
-            "power_delivered_watts",
-            "The amount of power that is currently being delivered to client in Watts",
-        ))
-        .unwrap();
-        registry
-            .register(Box::new(power_delivered_watts.clone()))
-            .unwrap();
-
-        let power_received_watts = Gauge::with_opts(Opts::new(
-            "power_received_watts",
-            "The amount of power that is currently being delivered by client in Watts",
-        ))
-        .unwrap();
-        registry
-            .register(Box::new(power_received_watts.clone()))
-            .unwrap();
-
-        // power_failures counter
-        let power_failures_total = IntCounter::with_opts(Opts::new(
-            "power_failures_total",
-            "Number of power failures in any phase",
-        ))
-        .unwrap();
-        registry
-            .register(Box::new(power_failures_total.clone()))
-            .unwrap();
-
-        // power_long_failures counter
-        let power_long_failures_total = IntCounter::with_opts(Opts::new(
-            "power_long_failures_total",
-            "Number of long power failures in any phase",
-        ))
-        .unwrap();
-        registry
-            .register(Box::new(power_long_failures_total.clone()))
-            .unwrap();
-
-        // voltage_sags counter {line}
-        let phase_voltage_sags_total = IntCounterVec::new(
-            Opts::new(
-                "phase_voltage_sags_total",
-                "Number of voltage sags in specified phase",
+        )?;
+        let energy_received_scale = config.scale("energy_received", 3600000.0);
+
+        let energy_tariff = register(
+            &registry,
+            config,
+            "energy_tariff",
+            IntGauge::with_opts(opts(
+                config,
+                "energy_tariff",
+                "energy_tariff",
+                "The currently active tariff",
+            )),
+        )?;
+
+        let power_delivered_watts = register(
+            &registry,
+            config,
+            "power_delivered",
+            Gauge::with_opts(opts(
+                config,
+                "power_delivered",
+                "power_delivered_watts",
+                "The amount of power that is currently being delivered to client in Watts",
+            )),
+        )?;
+        let power_delivered_scale = config.scale("power_delivered", 1000.0);
+
+        let power_received_watts = register(
+            &registry,
+            config,
+            "power_received",
+            Gauge::with_opts(opts(
+                config,
+                "power_received",
+                "power_received_watts",
+                "The amount of power that is currently being delivered by client in Watts",
+            )),
+        )?;
+        let power_received_scale = config.scale("power_received", 1000.0);
+
+        let power_failures_total = register(
+            &registry,
+            config,
+            "power_failures",
+            IntCounter::with_opts(opts(
+                config,
+                "power_failures",
+                "power_failures_total",
+                "Number of power failures in any phase",
+            )),
+        )?;
+
+        let power_long_failures_total = register(
+            &registry,
+            config,
+            "power_long_failures",
+            IntCounter::with_opts(opts(
+                config,
+                "power_long_failures",
+                "power_long_failures_total",
+                "Number of long power failures in any phase",
+            )),
+        )?;
+
+        let phase_voltage_sags_total = register(
+            &registry,
+            config,
+            "phase_voltage_sags",
+            IntCounterVec::new(
+                opts(
+                    config,
+                    "phase_voltage_sags",
+                    "phase_voltage_sags_total",
+                    "Number of voltage sags in specified phase",
+                ),
+                &["phase"],
             ),
-            &["phase"],
-        )
-        .unwrap();
-        registry
-            .register(Box::new(phase_voltage_sags_total.clone()))
-            .unwrap();
-
-        // voltage_swells counter {line}
-        let phase_voltage_swells_total = IntCounterVec::new(
-            Opts::new(
-                "phase_voltage_swells_total",
-                "Number of voltage swells in specified phase",
+        )?;
+
+        let phase_voltage_swells_total = register(
+            &registry,
+            config,
+            "phase_voltage_swells",
+            IntCounterVec::new(
+                opts(
+                    config,
+                    "phase_voltage_swells",
+                    "phase_voltage_swells_total",
+                    "Number of voltage swells in specified phase",
+                ),
+                &["phase"],
             ),
-            &["phase"],
-        )
-        .unwrap();
-        registry
-            .register(Box::new(phase_voltage_swells_total.clone()))
-            .unwrap();
-
-        // voltage gauge {line}
-        let phase_voltage_volts = GaugeVec::new(
-            Opts::new(
-                "phase_voltage_volts",
-                "Instantaneous voltage in specified phase in Volts",
+        )?;
+
+        let phase_voltage_volts = register(
+            &registry,
+            config,
+            "phase_voltage",
+            GaugeVec::new(
+                opts(
+                    config,
+                    "phase_voltage",
+                    "phase_voltage_volts",
+                    "Instantaneous voltage in specified phase in Volts",
+                ),
+                &["phase"],
             ),
-            &["phase"],
-        )
-        .unwrap();
-        registry
-            .register(Box::new(phase_voltage_volts.clone()))
-            .unwrap();
-
-        // current gauge {line}
-        let phase_current_amperes = GaugeVec::new(
-            Opts::new(
-                "phase_current_amperes",
-                "Instantaneous current in specified phase in Ampères",
+        )?;
+        let phase_voltage_scale = config.scale("phase_voltage", 1.0);
+
+        let phase_current_amperes = register(
+            &registry,
+            config,
+            "phase_current",
+            GaugeVec::new(
+                opts(
+                    config,
+                    "phase_current",
+                    "phase_current_amperes",
+                    "Instantaneous current in specified phase in Ampères",
+                ),
+                &["phase"],
             ),
-            &["phase"],
-        )
-        .unwrap();
-        registry
-            .register(Box::new(phase_current_amperes.clone()))
-            .unwrap();
-
-        // active_power_positive gauge {line}
-        let phase_active_power_positive_watts = GaugeVec::new(
-            Opts::new(
-                "phase_active_power_positive_watts",
-                "Instantaneous active power (+P) in specified phase in Watts",
+        )?;
+        let phase_current_scale = config.scale("phase_current", 1.0);
+
+        let phase_active_power_positive_watts = register(
+            &registry,
+            config,
+            "phase_active_power_positive",
+            GaugeVec::new(
+                opts(
+                    config,
+                    "phase_active_power_positive",
+                    "phase_active_power_positive_watts",
+                    "Instantaneous active power (+P) in specified phase in Watts",
+                ),
+                &["phase"],
             ),
-            &["phase"],
-        )
-        .unwrap();
-        registry
-            .register(Box::new(phase_active_power_positive_watts.clone()))
-            .unwrap();
-
-        // active_power_negative gauge {line}
-        let phase_active_power_negative_watts = GaugeVec::new(
+        )?;
+        let phase_active_power_positive_scale =
+            config.scale("phase_active_power_positive", 1000.0);
+
+        let phase_active_power_negative_watts = register(
+            &registry,
+            config,
+            "phase_active_power_negative",
+            GaugeVec::new(
+                opts(
+                    config,
+                    "phase_active_power_negative",
+                    "phase_active_power_negative_watts",
+                    "Instantaneous active power (-P) in specified phase in Watts",
+                ),
+                &["phase"],
+            ),
+        )?;
+        let phase_active_power_negative_scale =
+            config.scale("phase_active_power_negative", 1000.0);
+
+        let gas_delivered_cubic_meters_total = register(
+            &registry,
+            config,
+            "gas_delivered",
+            Counter::with_opts(opts(
+                config,
+                "gas_delivered",
+                "gas_delivered_cubic_meters_total",
+                "Amount of natural gas delivered to client in cubic meters",
+            )),
+        )?;
+        let gas_delivered_scale = config.scale("gas_delivered", 1.0);
+
+        // dsmr_frames_decoded_total counter
+        let frames_decoded_total = IntCounter::with_opts(Opts::new(
+            "dsmr_frames_decoded_total",
+            "Number of telegrams successfully decoded",
+        ))?;
+        internal_registry.register(Box::new(frames_decoded_total.clone()))?;
+
+        // dsmr_decode_errors_total counter {cause}
+        let decode_errors_total = IntCounterVec::new(
             Opts::new(
-                "phase_active_power_negative_watts",
-                "Instantaneous active power (-P) in specified phase in Watts",
+                "dsmr_decode_errors_total",
+                "Number of telegrams that failed to decode, by cause",
             ),
-            &["phase"],
-        )
-        .unwrap();
-        registry
-            .register(Box::new(phase_active_power_negative_watts.clone()))
-            .unwrap();
-
-        // gas_delivered counter (m3)
-        let gas_delivered_cubic_meters_total = Counter::with_opts(Opts::new(
-            "gas_delivered_cubic_meters_total",
-            "Amount of natural gas delivered to client in cubic meters",
-        ))
-        .unwrap();
-        registry
-            .register(Box::new(gas_delivered_cubic_meters_total.clone()))
-            .unwrap();
-
-        Metrics {
+            &["cause"],
+        )?;
+        internal_registry.register(Box::new(decode_errors_total.clone()))?;
+
+        // dsmr_serial_reconnects_total counter
+        let serial_reconnects_total = IntCounter::with_opts(Opts::new(
+            "dsmr_serial_reconnects_total",
+            "Number of attempts to reopen the input source after it failed to open or its stream ended",
+        ))?;
+        internal_registry.register(Box::new(serial_reconnects_total.clone()))?;
+
+        // dsmr_last_frame_timestamp_seconds gauge
+        let last_frame_timestamp_seconds = Gauge::with_opts(Opts::new(
+            "dsmr_last_frame_timestamp_seconds",
+            "Unix timestamp of the last successfully decoded telegram",
+        ))?;
+        internal_registry.register(Box::new(last_frame_timestamp_seconds.clone()))?;
+
+        Ok(Metrics {
             encoder: TextEncoder::new(),
             registry,
+            internal_registry,
             energy_delivered_joules_total,
+            energy_delivered_scale,
             energy_received_joules_total,
+            energy_received_scale,
             energy_tariff,
             power_delivered_watts,
+            power_delivered_scale,
             power_received_watts,
+            power_received_scale,
             power_failures_total,
             power_long_failures_total,
             phase_voltage_sags_total,
             phase_voltage_swells_total,
             phase_voltage_volts,
+            phase_voltage_scale,
             phase_current_amperes,
+            phase_current_scale,
             phase_active_power_negative_watts,
+            phase_active_power_negative_scale,
             phase_active_power_positive_watts,
+            phase_active_power_positive_scale,
             gas_delivered_cubic_meters_total,
+            gas_delivered_scale,
+            frames_decoded_total,
+            decode_errors_total,
+            serial_reconnects_total,
+            last_frame_timestamp_seconds,
             last_update: Instant::now() - METRICS_TTL,
-        }
+        })
+    }
+
+    /// Records a successfully decoded telegram.
+    pub fn record_frame_decoded(&mut self) {
+        self.frames_decoded_total.inc();
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.last_frame_timestamp_seconds.set(now.as_secs_f64());
+    }
+
+    /// Records a telegram that failed to decode, labelled with its cause
+    /// (see [`crate::decoder::DecodeError::cause`]).
+    pub fn record_decode_error(&mut self, cause: &str) {
+        self.decode_errors_total.with_label_values(&[cause]).inc();
+    }
+
+    /// Records an attempt to reopen the input source after it failed to open
+    /// or its stream ended.
+    pub fn record_reconnect(&mut self) {
+        self.serial_reconnects_total.inc();
     }
 
     pub fn update(&mut self, state: &dsmr5::state::State) {
-        for (i, reading) in state.meterreadings.iter().enumerate() {
-            if let Some(energy_delivered_kwh) = reading.to {
-                let counter = self
-                    .energy_delivered_joules_total
-                    .with_label_values(&[&(i + 1).to_string()]);
-                counter.inc_by(energy_delivered_kwh * 3600000.0 - counter.get());
+        if let Some(metric) = &self.energy_delivered_joules_total {
+            for (i, reading) in state.meterreadings.iter().enumerate() {
+                if let Some(energy_delivered_kwh) = reading.to {
+                    let counter = metric.with_label_values(&[&(i + 1).to_string()]);
+                    counter.inc_by(energy_delivered_kwh * self.energy_delivered_scale - counter.get());
+                }
             }
+        }
 
-            if let Some(energy_received_kwh) = reading.by {
-                let counter = self
-                    .energy_received_joules_total
-                    .with_label_values(&[&(i + 1).to_string()]);
-                counter.inc_by(energy_received_kwh * 3600000.0 - counter.get());
+        if let Some(metric) = &self.energy_received_joules_total {
+            for (i, reading) in state.meterreadings.iter().enumerate() {
+                if let Some(energy_received_kwh) = reading.by {
+                    let counter = metric.with_label_values(&[&(i + 1).to_string()]);
+                    counter.inc_by(energy_received_kwh * self.energy_received_scale - counter.get());
+                }
             }
         }
 
-        if let Some(energy_tariff) = state.tariff_indicator {
-            self.energy_tariff.set(i64::from_be_bytes([
+        if let (Some(metric), Some(energy_tariff)) = (&self.energy_tariff, state.tariff_indicator) {
+            metric.set(i64::from_be_bytes([
                 0,
                 0,
                 0,
@@ -239,82 +405,104 @@ impl Metrics {
             ]));
         }
 
-        if let Some(power_delivered) = state.power_delivered {
-            self.power_delivered_watts.set(power_delivered * 1000.0);
+        if let (Some(metric), Some(power_delivered)) =
+            (&self.power_delivered_watts, state.power_delivered)
+        {
+            metric.set(power_delivered * self.power_delivered_scale);
         }
 
-        if let Some(power_received) = state.power_received {
-            self.power_received_watts.set(power_received * 1000.0);
+        if let (Some(metric), Some(power_received)) =
+            (&self.power_received_watts, state.power_received)
+        {
+            metric.set(power_received * self.power_received_scale);
         }
 
-        if let Some(power_failures) = state.power_failures {
-            self.power_failures_total
-                .inc_by(power_failures - self.power_failures_total.get());
+        if let (Some(metric), Some(power_failures)) =
+            (&self.power_failures_total, state.power_failures)
+        {
+            metric.inc_by(power_failures - metric.get());
         }
 
-        if let Some(long_power_failures) = state.long_power_failures {
-            self.power_long_failures_total
-                .inc_by(long_power_failures - self.power_long_failures_total.get());
+        if let (Some(metric), Some(long_power_failures)) =
+            (&self.power_long_failures_total, state.long_power_failures)
+        {
+            metric.inc_by(long_power_failures - metric.get());
         }
 
         for (i, line) in state.lines.iter().enumerate() {
-            if let Some(voltage_sags) = line.voltage_sags {
-                let counter = self
-                    .phase_voltage_sags_total
-                    .with_label_values(&[&(i + 1).to_string()]);
+            if let (Some(metric), Some(voltage_sags)) =
+                (&self.phase_voltage_sags_total, line.voltage_sags)
+            {
+                let counter = metric.with_label_values(&[&(i + 1).to_string()]);
                 counter.inc_by(voltage_sags - counter.get());
             }
 
-            if let Some(voltage_swells) = line.voltage_swells {
-                let counter = self
-                    .phase_voltage_swells_total
-                    .with_label_values(&[&(i + 1).to_string()]);
+            if let (Some(metric), Some(voltage_swells)) =
+                (&self.phase_voltage_swells_total, line.voltage_swells)
+            {
+                let counter = metric.with_label_values(&[&(i + 1).to_string()]);
                 counter.inc_by(voltage_swells - counter.get());
             }
 
-            if let Some(voltage) = line.voltage {
-                self.phase_voltage_volts
+            if let (Some(metric), Some(voltage)) = (&self.phase_voltage_volts, line.voltage) {
+                metric
                     .with_label_values(&[&(i + 1).to_string()])
-                    .set(voltage);
+                    .set(voltage * self.phase_voltage_scale);
             }
 
-            if let Some(current) = line.current {
-                self.phase_current_amperes
+            if let (Some(metric), Some(current)) = (&self.phase_current_amperes, line.current) {
+                metric
                     .with_label_values(&[&(i + 1).to_string()])
-                    .set(current as f64);
+                    .set(current as f64 * self.phase_current_scale);
             }
 
-            if let Some(active_power_positive) = line.active_power_plus {
-                self.phase_active_power_positive_watts
+            if let (Some(metric), Some(active_power_positive)) = (
+                &self.phase_active_power_positive_watts,
+                line.active_power_plus,
+            ) {
+                metric
                     .with_label_values(&[&(i + 1).to_string()])
-                    .set(active_power_positive * 1000.0);
+                    .set(active_power_positive * self.phase_active_power_positive_scale);
             }
 
-            if let Some(active_power_negative) = line.active_power_neg {
-                self.phase_active_power_negative_watts
+            if let (Some(metric), Some(active_power_negative)) = (
+                &self.phase_active_power_negative_watts,
+                line.active_power_neg,
+            ) {
+                metric
                     .with_label_values(&[&(i + 1).to_string()])
-                    .set(active_power_negative * 1000.0);
+                    .set(active_power_negative * self.phase_active_power_negative_scale);
             }
         }
 
-        if let Some(gas_slave) = state
-            .slaves
-            .iter()
-            .find(|slave| slave.device_type == Some(3))
-        {
-            if let Some((_, reading)) = gas_slave.meter_reading {
-                self.gas_delivered_cubic_meters_total
-                    .inc_by(reading - self.gas_delivered_cubic_meters_total.get());
+        if let Some(metric) = &self.gas_delivered_cubic_meters_total {
+            if let Some(gas_slave) = state
+                .slaves
+                .iter()
+                .find(|slave| slave.device_type == Some(3))
+            {
+                if let Some((_, reading)) = gas_slave.meter_reading {
+                    metric.inc_by(reading * self.gas_delivered_scale - metric.get());
+                }
             }
         }
 
         self.last_update = Instant::now();
     }
 
+    /// Encodes the current metrics. Meter-derived metrics are blanked once
+    /// `last_update` is older than `METRICS_TTL`, but the exporter's own
+    /// self-observability metrics are always encoded so a stalled meter can
+    /// be told apart from a healthy-but-idle one.
     pub fn encode(&self) -> Result<String, Box<dyn error::Error>> {
-        let metrics = self.registry.gather();
         let mut buffer = vec![];
-        self.encoder.encode(&metrics, &mut buffer)?;
+
+        if self.last_update.elapsed() <= METRICS_TTL {
+            self.encoder.encode(&self.registry.gather(), &mut buffer)?;
+        }
+
+        self.encoder
+            .encode(&self.internal_registry.gather(), &mut buffer)?;
 
         Ok(String::from_utf8(buffer)?)
     }
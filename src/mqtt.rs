@@ -0,0 +1,182 @@
+use std::{sync::Arc, time::Duration};
+
+use backoff::{backoff::Backoff, ExponentialBackoffBuilder};
+use dsmr5::state::State;
+use rumqttc::{AsyncClient, MqttOptions, QoS};
+use tokio::sync::broadcast;
+
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub topic_prefix: String,
+}
+
+struct Sensor {
+    object_id: &'static str,
+    name: &'static str,
+    unit_of_measurement: &'static str,
+    device_class: Option<&'static str>,
+    state_class: Option<&'static str>,
+}
+
+const SENSORS: &[Sensor] = &[
+    Sensor {
+        object_id: "power_delivered_watts",
+        name: "Power delivered",
+        unit_of_measurement: "W",
+        device_class: Some("power"),
+        state_class: Some("measurement"),
+    },
+    Sensor {
+        object_id: "power_received_watts",
+        name: "Power received",
+        unit_of_measurement: "W",
+        device_class: Some("power"),
+        state_class: Some("measurement"),
+    },
+    Sensor {
+        object_id: "gas_delivered_cubic_meters_total",
+        name: "Gas delivered",
+        unit_of_measurement: "m³",
+        device_class: Some("gas"),
+        state_class: Some("total_increasing"),
+    },
+    Sensor {
+        object_id: "phase_voltage_volts_l1",
+        name: "Voltage L1",
+        unit_of_measurement: "V",
+        device_class: Some("voltage"),
+        state_class: Some("measurement"),
+    },
+    Sensor {
+        object_id: "phase_voltage_volts_l2",
+        name: "Voltage L2",
+        unit_of_measurement: "V",
+        device_class: Some("voltage"),
+        state_class: Some("measurement"),
+    },
+    Sensor {
+        object_id: "phase_voltage_volts_l3",
+        name: "Voltage L3",
+        unit_of_measurement: "V",
+        device_class: Some("voltage"),
+        state_class: Some("measurement"),
+    },
+];
+
+/// Connects to the broker, publishes Home Assistant discovery configs once,
+/// then publishes a non-retained state message for every decoded telegram
+/// received on `states`, until the channel closes or shutdown is requested.
+pub async fn run(
+    config: MqttConfig,
+    mut states: broadcast::Receiver<Arc<State>>,
+    mut notify_shutdown: broadcast::Receiver<()>,
+) {
+    let mut mqtt_options = MqttOptions::new("dsmr5-exporter", config.host.clone(), config.port);
+    mqtt_options.set_keep_alive(Duration::from_secs(30));
+
+    let (client, mut event_loop) = AsyncClient::new(mqtt_options, 16);
+
+    let event_loop_task = tokio::spawn(async move {
+        let mut reconnect_backoff = ExponentialBackoffBuilder::default()
+            .with_max_interval(Duration::from_millis(5000))
+            .with_max_elapsed_time(None)
+            .build();
+
+        loop {
+            match event_loop.poll().await {
+                Ok(_) => reconnect_backoff.reset(),
+                Err(error) => {
+                    println!("mqtt connection error: {:?}", error);
+                    if let Some(delay) = reconnect_backoff.next_backoff() {
+                        tokio::time::sleep(delay).await;
+                    }
+                }
+            }
+        }
+    });
+
+    publish_discovery(&client, &config).await;
+
+    loop {
+        tokio::select! {
+            state = states.recv() => match state {
+                Ok(state) => publish_state(&client, &config, &state).await,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    println!("mqtt publisher lagged behind, skipped {} frames", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = notify_shutdown.recv() => {
+                println!("stopping mqtt publisher");
+                break;
+            }
+        }
+    }
+
+    event_loop_task.abort();
+}
+
+async fn publish_discovery(client: &AsyncClient, config: &MqttConfig) {
+    for sensor in SENSORS {
+        let state_topic = format!("{}/{}", config.topic_prefix, sensor.object_id);
+        let unique_id = format!("{}_{}", config.topic_prefix, sensor.object_id);
+        let payload = serde_json::json!({
+            "name": sensor.name,
+            "unique_id": unique_id,
+            "state_topic": state_topic,
+            "unit_of_measurement": sensor.unit_of_measurement,
+            "device_class": sensor.device_class,
+            "state_class": sensor.state_class,
+            "device": {
+                "identifiers": [config.topic_prefix],
+                "name": "DSMR5 smart meter",
+            },
+        });
+        let topic = format!(
+            "homeassistant/sensor/{}_{}/config",
+            config.topic_prefix, sensor.object_id
+        );
+
+        if let Err(error) = client
+            .publish(topic, QoS::AtLeastOnce, true, payload.to_string())
+            .await
+        {
+            println!("failed to publish discovery config for {}: {:?}", sensor.object_id, error);
+        }
+    }
+}
+
+async fn publish_state(client: &AsyncClient, config: &MqttConfig, state: &State) {
+    if let Some(power_delivered) = state.power_delivered {
+        publish(client, config, "power_delivered_watts", power_delivered * 1000.0).await;
+    }
+
+    if let Some(power_received) = state.power_received {
+        publish(client, config, "power_received_watts", power_received * 1000.0).await;
+    }
+
+    if let Some(gas_slave) = state.slaves.iter().find(|slave| slave.device_type == Some(3)) {
+        if let Some((_, reading)) = gas_slave.meter_reading {
+            publish(client, config, "gas_delivered_cubic_meters_total", reading).await;
+        }
+    }
+
+    for (i, line) in state.lines.iter().enumerate() {
+        if let Some(voltage) = line.voltage {
+            let object_id = format!("phase_voltage_volts_l{}", i + 1);
+            publish(client, config, &object_id, voltage).await;
+        }
+    }
+}
+
+async fn publish(client: &AsyncClient, config: &MqttConfig, object_id: &str, value: f64) {
+    let topic = format!("{}/{}", config.topic_prefix, object_id);
+
+    if let Err(error) = client
+        .publish(topic, QoS::AtLeastOnce, false, value.to_string())
+        .await
+    {
+        println!("failed to publish {}: {:?}", object_id, error);
+    }
+}
@@ -0,0 +1,75 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+
+/// A P1 input source: either a local serial device or a `tcp://host:port`
+/// ser2net/ESP bridge. `Dsmr5Codec` frames either the same way.
+pub enum Port {
+    Serial(SerialStream),
+    Tcp(TcpStream),
+}
+
+impl Port {
+    /// Opens `device` as a TCP connection if it is a `tcp://host:port` URL,
+    /// otherwise as a local serial port.
+    pub async fn open(device: &str) -> io::Result<Self> {
+        if let Some(address) = device.strip_prefix("tcp://") {
+            println!("opening tcp connection to {}", address);
+            TcpStream::connect(address).await.map(Port::Tcp)
+        } else {
+            println!("opening serial port");
+            tokio_serial::new(device, 115200)
+                .open_native_async()
+                .map(Port::Serial)
+                .map_err(io::Error::from)
+        }
+    }
+}
+
+impl AsyncRead for Port {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Port::Serial(port) => Pin::new(port).poll_read(cx, buf),
+            Port::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Port {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Port::Serial(port) => Pin::new(port).poll_write(cx, buf),
+            Port::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Port::Serial(port) => Pin::new(port).poll_flush(cx),
+            Port::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Port::Serial(port) => Pin::new(port).poll_shutdown(cx),
+            Port::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}